@@ -1,17 +1,18 @@
 #![deny(warnings)]
 
 extern crate arg_parser;
-extern crate extra;
+extern crate memchr;
 
 use std::cell::Cell; // Provide mutable fields in immutable structs
 use std::env;
-use std::error::Error;
 use std::fs;
-use std::io::{self, BufReader, Read, Stderr, StdoutLock, Write};
+use std::io::{self, BufReader, BufWriter, Read, Stderr, StdoutLock, Write};
 use std::process::exit;
-use extra::option::OptionalExt;
 use arg_parser::ArgParser;
 
+/// Capacity of the `BufWriter` wrapping stdout, tuned to keep write() syscalls large and rare.
+const BUFWRITER_CAPACITY: usize = 64 * 1024;
+
 const MAN_PAGE: &'static str = /* @MANSTART{cat} */ r#"NAME
     cat - concatenate files and print on the standard output
 
@@ -59,6 +60,10 @@ OPTIONS
     --show-nonprinting
         use caret (^) and M- notation, except for LFD and TAB.
 
+    -z
+    --zero-terminated
+        line delimiter is NUL, not newline
+
     -h
     --help
         display this help and exit
@@ -75,12 +80,13 @@ struct Program {
     show_tabs:        bool,
     show_nonprinting: bool,
     squeeze_blank:    bool,
+    zero_terminated:  bool,
     paths:            Vec<String>,
 }
 
 impl Program {
     /// Initialize the program's arguments and flags.
-    fn initialize(stdout: &mut StdoutLock, stderr: &mut Stderr) -> Program {
+    fn initialize(stdout: &mut BufWriter<StdoutLock>) -> Program {
         let mut parser = ArgParser::new(10).
             add_flag(&["A", "show-all"]). //vET
             add_flag(&["b", "number-nonblank"]).
@@ -91,6 +97,7 @@ impl Program {
             add_flag(&["t"]). //vT
             add_flag(&["T", "show-tabs"]).
             add_flag(&["v", "show-nonprinting"]).
+            add_flag(&["z", "zero-terminated"]).
             add_flag(&["h", "help"]);
         parser.parse(env::args());
 
@@ -102,12 +109,13 @@ impl Program {
             show_tabs:        false,
             show_nonprinting: false,
             squeeze_blank:    false,
+            zero_terminated:  false,
             paths:            Vec::with_capacity(parser.args.len()),
         };
 
         if parser.found("help") {
-            stdout.write(MAN_PAGE.as_bytes()).try(stderr);
-            stdout.flush().try(stderr);
+            let _ = stdout.write_all(MAN_PAGE.as_bytes());
+            let _ = stdout.flush();
             exit(0);
         }
 
@@ -143,215 +151,307 @@ impl Program {
             cat.show_nonprinting = true;
         }
 
+        if parser.found("zero-terminated") {
+            cat.zero_terminated = true;
+        }
+
         if !parser.args.is_empty() {
             cat.paths = parser.args;
         }
         cat
     }
 
-    /// Execute the parameters given to the program.
-    fn and_execute(&self, stdout: &mut StdoutLock, stderr: &mut Stderr) -> i32 {
+    /// Execute the parameters given to the program, returning the process exit status.
+    fn and_execute(&self, stdout: &mut BufWriter<StdoutLock>, stderr: &mut Stderr) -> i32 {
         let stdin = io::stdin();
         let line_count = &mut 0usize;
         let flags_enabled = self.number || self.number_nonblank || self.show_ends || self.show_tabs ||
                             self.squeeze_blank || self.show_nonprinting;
 
-        if self.paths.is_empty() && flags_enabled {
-            self.cat(&mut stdin.lock(), line_count, stdout, stderr);
-        } else if self.paths.is_empty() {
-            self.simple_cat(&mut stdin.lock(), stdout, stderr);
+        if self.paths.is_empty() {
+            let result = if flags_enabled {
+                self.cat(&mut stdin.lock(), line_count, stdout)
+            } else {
+                self.simple_cat(&mut stdin.lock(), stdout)
+            };
+            if let Err(error) = result {
+                if self.record_error(stderr, "-", error) {
+                    return self.exit_status.get();
+                }
+            }
         } else {
             for path in &self.paths {
-                if flags_enabled && path == "-" {
-                    self.cat(&mut stdin.lock(), line_count, stdout, stderr);
+                let result = if flags_enabled && path == "-" {
+                    self.cat(&mut stdin.lock(), line_count, stdout)
                 } else if path == "-" {
                     // Copy the standard input directly to the standard output.
-                    self.simple_cat(&mut stdin.lock(), stdout, stderr);
+                    self.simple_cat(&mut stdin.lock(), stdout)
                 } else if fs::metadata(&path).map(|m| m.is_dir()).unwrap_or(false) {
-                    stderr.write(path.as_bytes()).try(stderr);
-                    stderr.write(b": Is a directory\n").try(stderr);
-                    stderr.flush().try(stderr);
-                    self.exit_status.set(1i32);
+                    Err(io::Error::other("Is a directory"))
                 } else if flags_enabled {
-                    fs::File::open(&path)
-                        // Open the file and copy the file's contents to standard output based input arguments.
-                        .map(|file| self.cat(&mut BufReader::new(file), line_count, stdout, stderr))
-                        // If an error occurred, print the error and set the exit status.
-                        .unwrap_or_else(|message| {
-                            stderr.write(path.as_bytes()).try(stderr);
-                            stderr.write(b": ").try(stderr);
-                            stderr.write(message.description().as_bytes()).try(stderr);
-                            stderr.write(b"\n").try(stderr);
-                            stderr.flush().try(stderr);
-                            self.exit_status.set(1i32);
-                        });
+                    // Open the file and copy its contents to standard output based on the input arguments.
+                    fs::File::open(&path).and_then(|file| self.cat(&mut BufReader::new(file), line_count, stdout))
                 } else {
-                    // Open a file and copy the contents directly to standard output.
-                    fs::File::open(&path).map(|ref mut file| { self.simple_cat(file, stdout, stderr); })
-                        // If an error occurs, print the error and set the exit status.
-                        .unwrap_or_else(|message| {
-                            stderr.write(path.as_bytes()).try(stderr);
-                            stderr.write(b": ").try(stderr);
-                            stderr.write(message.description().as_bytes()).try(stderr);
-                            stderr.write(b"\n").try(stderr);
-                            stderr.flush().try(stderr);
-                            self.exit_status.set(1i32);
-                        });
+                    // Open a file and let the kernel copy its contents directly to standard output.
+                    fs::File::open(&path).and_then(|ref mut file| self.simple_cat_file(file, stdout))
+                };
+
+                if let Err(error) = result {
+                    if self.record_error(stderr, path, error) {
+                        return self.exit_status.get();
+                    }
                 }
             }
         }
+
+        if let Err(error) = stdout.flush() {
+            if self.record_error(stderr, "-", error) {
+                return self.exit_status.get();
+            }
+        }
         self.exit_status.get()
     }
 
-    /// A simple cat that runs a lot faster than self.cat() due to no iterators over single bytes.
-    fn simple_cat<F: Read>(&self, file: &mut F, stdout: &mut StdoutLock, stderr: &mut Stderr) { 
+    /// Report a non-fatal I/O error for `path`, returning `true` if processing should stop.
+    fn record_error(&self, stderr: &mut Stderr, path: &str, error: io::Error) -> bool {
+        if is_broken_pipe(&error) {
+            return true;
+        }
+        print_error(stderr, path, &error);
+        self.exit_status.set(1i32);
+        false
+    }
+
+    /// A simple cat for stdin and other non-seekable readers; regular files use `simple_cat_file`.
+    fn simple_cat<F: Read>(&self, file: &mut F, stdout: &mut BufWriter<StdoutLock>) -> io::Result<()> {
         let mut buf: [u8; 8*8192] = [0; 8*8192]; // 64K seems to be the sweet spot for a buffer on my machine.
-        loop { 
-            let n_read = file.read(&mut buf).try(stderr);
+        loop {
+            let n_read = file.read(&mut buf)?;
             if n_read == 0 { // We've reached the end of the input
                 break;
             }
-            stdout.write_all(&buf[..n_read]).try(stderr);
+            stdout.write_all(&buf[..n_read])?;
+        }
+        Ok(())
+    }
+
+    /// Zero-copy cat for regular files via `std::io::copy`, bypassing the `BufWriter`.
+    fn simple_cat_file(&self, file: &mut fs::File, stdout: &mut BufWriter<StdoutLock>) -> io::Result<()> {
+        stdout.flush()?;
+        io::copy(file, stdout.get_mut())?;
+        Ok(())
+    }
+
+    /// The byte used to delimit records: NUL when `-z` is active, LF otherwise.
+    fn separator(&self) -> u8 {
+        if self.zero_terminated { 0 } else { b'\n' }
+    }
+
+    /// Cats either a file or stdin, dispatching to `cat_fast` unless `-v` requires `cat_scalar`.
+    fn cat<F: Read>(&self, file: &mut F, line_count: &mut usize, stdout: &mut BufWriter<StdoutLock>) -> io::Result<()> {
+        if self.show_nonprinting {
+            self.cat_scalar(file, line_count, stdout)
+        } else {
+            self.cat_fast(file, line_count, stdout)
+        }
+    }
+
+    /// Close out the record ending at the separator: prefix, squeeze-blank, show-ends, then reset.
+    fn close_record(&self, out_buf: &mut Vec<u8>, line_count: &mut usize, separator: u8,
+                     record_has_content: &mut bool, prefix_printed: &mut bool,
+                     last_line_was_blank: &mut bool) -> io::Result<()> {
+        if !*prefix_printed && self.number {
+            write_number_prefix(out_buf, line_count)?;
+        }
+
+        let is_blank = !*record_has_content;
+        if !(is_blank && self.squeeze_blank && *last_line_was_blank) {
+            if self.show_ends {
+                out_buf.write_all(&[b'$'])?;
+            }
+            out_buf.write_all(&[separator])?;
         }
+
+        *last_line_was_blank = is_blank;
+        *record_has_content = false;
+        *prefix_printed = false;
+        Ok(())
     }
 
-    /// Cats either a file or stdin based on the flag arguments given to the program.
-    fn cat<F: Read>(&self, file: &mut F, line_count: &mut usize, stdout: &mut StdoutLock, stderr: &mut Stderr) {
-        let mut character_count = 0;
+    /// Byte-by-byte cat supporting the full `-v`/M-notation rendering.
+    fn cat_scalar<F: Read>(&self, file: &mut F, line_count: &mut usize, stdout: &mut BufWriter<StdoutLock>) -> io::Result<()> {
+        let separator = self.separator();
+        let mut record_has_content = false;
+        let mut prefix_printed = false;
         let mut last_line_was_blank = false;
         let mut buf: [u8; 8*8192] = [0; 8*8192]; // 64K seems to be the sweet spot for a buffer on my machine.
         let mut out_buf: Vec<u8> = Vec::with_capacity(24*8192); // Worst case 2 chars out per char
-        loop { 
-            let n_read = file.read(&mut buf).try(stderr);
+        loop {
+            let n_read = file.read(&mut buf)?;
             if n_read == 0 { // We've reached the end of the input
                 break;
             }
 
             for &byte in buf[0..n_read].iter() {
-                if character_count == 0 && (self.number || (self.number_nonblank && byte != b'\n')) {
-                    out_buf.write(b"     ").try(stderr);
-                    out_buf.write(line_count.to_string().as_bytes()).try(stderr);
-                    out_buf.write(b"  ").try(stderr);
-                    *line_count += 1;
+                if byte != separator {
+                    if !prefix_printed && (self.number || self.number_nonblank) {
+                        write_number_prefix(&mut out_buf, line_count)?;
+                        prefix_printed = true;
+                    }
+                    record_has_content = true;
                 }
                 match byte {
+                    _ if byte == separator => {
+                        self.close_record(&mut out_buf, line_count, separator, &mut record_has_content,
+                                           &mut prefix_printed, &mut last_line_was_blank)?;
+                    },
+                    // A literal LF that isn't the active separator (only possible in -z mode) is
+                    // ordinary record content, not a control character.
+                    10 => {
+                        out_buf.write_all(&[byte])?;
+                    },
                     0...8 | 11...31 => if self.show_nonprinting {
-                        push_caret(&mut out_buf, stderr, byte+64);
-                        count_character(&mut character_count, &self.number, &self.number_nonblank);
+                        push_caret(&mut out_buf, byte+64)?;
                     },
                     9 => {
                         if self.show_tabs {
-                            push_caret(&mut out_buf, stderr, b'I');
+                            push_caret(&mut out_buf, b'I')?;
                         } else {
-                            out_buf.write(&[byte]).try(stderr);
+                            out_buf.write_all(&[byte])?;
                         }
-                        count_character(&mut character_count, &self.number, &self.number_nonblank);
                     }
-                    10 => {
-                        if character_count == 0 {
-                            if self.squeeze_blank && last_line_was_blank {
-                                continue
-                            } else if !last_line_was_blank {
-                                last_line_was_blank = true;
-                            }
-                        } else {
-                            last_line_was_blank = false;
-                            character_count = 0;
-                        }
-                        if self.show_ends {
-                            out_buf.write(b"$\n").try(stderr);
-                        } else {
-                            out_buf.write(b"\n").try(stderr);
-                        }
-                    },
                     32...126 => {
-                        out_buf.write(&[byte]).try(stderr);
-                        count_character(&mut character_count, &self.number, &self.number_nonblank);
+                        out_buf.write_all(&[byte])?;
                     },
                     127 => if self.show_nonprinting {
-                        push_caret(&mut out_buf, stderr, b'?');
-                        count_character(&mut character_count, &self.number, &self.number_nonblank);
+                        push_caret(&mut out_buf, b'?')?;
                     },
                     128...159 => if self.show_nonprinting {
-                        out_buf.write(b"M-^").try(stderr);
-                        out_buf.write(&[byte-64]).try(stderr);
-                        count_character(&mut character_count, &self.number, &self.number_nonblank);
+                        out_buf.write_all(b"M-^")?;
+                        out_buf.write_all(&[byte-64])?;
                     } else {
-                        out_buf.write(&[byte]).try(stderr);
-                        count_character(&mut character_count, &self.number, &self.number_nonblank);
+                        out_buf.write_all(&[byte])?;
                     },
                     _ => if self.show_nonprinting {
-                        out_buf.write(b"M-").try(stderr);
-                        out_buf.write(&[byte-128]).try(stderr);
-                        count_character(&mut character_count, &self.number, &self.number_nonblank);
+                        out_buf.write_all(b"M-")?;
+                        out_buf.write_all(&[byte-128])?;
                     } else {
-                        out_buf.write(&[byte]).try(stderr);
-                        count_character(&mut character_count, &self.number, &self.number_nonblank);
+                        out_buf.write_all(&[byte])?;
                     },
                 }
             }
-            stdout.write_all(&out_buf).try(stderr);
+            stdout.write_all(&out_buf)?;
             out_buf.clear();
         }
+        Ok(())
     }
-}
-/// Increase the character count by one if number printing is enabled.
-fn count_character(character_count: &mut usize, number: &bool, number_nonblank: &bool) {
-    if *number || *number_nonblank {
-        *character_count += 1;
+
+    /// memchr-accelerated line-at-a-time cat for `-n`/`-b`/`-E`/`-s`/`-T`, used whenever `-v` is not in play.
+    fn cat_fast<F: Read>(&self, file: &mut F, line_count: &mut usize, stdout: &mut BufWriter<StdoutLock>) -> io::Result<()> {
+        let separator = self.separator();
+        let mut record_has_content = false;
+        let mut prefix_printed = false;
+        let mut last_line_was_blank = false;
+        let mut buf: [u8; 8*8192] = [0; 8*8192]; // 64K seems to be the sweet spot for a buffer on my machine.
+        let mut out_buf: Vec<u8> = Vec::with_capacity(24*8192); // Worst case 2 chars out per char
+        loop {
+            let n_read = file.read(&mut buf)?;
+            if n_read == 0 { // We've reached the end of the input
+                break;
+            }
+
+            let mut pos = 0;
+            while pos < n_read {
+                let boundary = memchr::memchr(separator, &buf[pos..n_read]);
+                let end = boundary.map(|offset| pos + offset).unwrap_or(n_read);
+                let segment = &buf[pos..end];
+
+                if !prefix_printed && !segment.is_empty() && (self.number || self.number_nonblank) {
+                    write_number_prefix(&mut out_buf, line_count)?;
+                    prefix_printed = true;
+                }
+
+                if !segment.is_empty() {
+                    write_segment(&mut out_buf, segment, self.show_tabs)?;
+                    record_has_content = true;
+                }
+
+                if boundary.is_some() {
+                    self.close_record(&mut out_buf, line_count, separator, &mut record_has_content,
+                                       &mut prefix_printed, &mut last_line_was_blank)?;
+                    pos = end + 1;
+                } else {
+                    pos = end;
+                }
+            }
+
+            stdout.write_all(&out_buf)?;
+            out_buf.clear();
+        }
+        Ok(())
     }
 }
 
 /// Print a caret notation to stdout.
-fn push_caret<T: Write>(stdout: &mut T, stderr: &mut Stderr, notation: u8) {
-    stdout.write(&[b'^']).try(stderr);
-    stdout.write(&[notation]).try(stderr);
+fn push_caret<T: Write>(stdout: &mut T, notation: u8) -> io::Result<()> {
+    stdout.write_all(&[b'^'])?;
+    stdout.write_all(&[notation])?;
+    Ok(())
+}
+
+/// Write the "     N  " line-number prefix used by `-n`/`-b` and advance the counter.
+fn write_number_prefix(out_buf: &mut Vec<u8>, line_count: &mut usize) -> io::Result<()> {
+    out_buf.write_all(b"     ")?;
+    out_buf.write_all(line_count.to_string().as_bytes())?;
+    out_buf.write_all(b"  ")?;
+    *line_count += 1;
+    Ok(())
+}
+
+/// Write a line segment, splicing in `^I` for every tab when `-T`/`--show-tabs` is active.
+fn write_segment(out_buf: &mut Vec<u8>, segment: &[u8], show_tabs: bool) -> io::Result<()> {
+    if !show_tabs {
+        return out_buf.write_all(segment);
+    }
+    let mut pos = 0;
+    while pos < segment.len() {
+        match memchr::memchr(b'\t', &segment[pos..]) {
+            Some(offset) => {
+                out_buf.write_all(&segment[pos..pos+offset])?;
+                out_buf.write_all(b"^I")?;
+                pos += offset + 1;
+            },
+            None => {
+                out_buf.write_all(&segment[pos..])?;
+                break;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Report a non-fatal, per-file error the way GNU `cat` does: `path: message`.
+fn print_error(stderr: &mut Stderr, path: &str, error: &io::Error) {
+    let _ = writeln!(stderr, "{}: {}", path, error);
+}
+
+/// A broken pipe on stdout (e.g. `cat bigfile | head`) should end the run quietly, not panic.
+fn is_broken_pipe(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::BrokenPipe
 }
 
 fn main() {
     let stdout = io::stdout();
-    let mut stdout = stdout.lock();
+    let mut stdout = BufWriter::with_capacity(BUFWRITER_CAPACITY, stdout.lock());
     let mut stderr = io::stderr();
-    exit(Program::initialize(&mut stdout, &mut stderr).and_execute(&mut stdout, &mut stderr));
+    exit(Program::initialize(&mut stdout).and_execute(&mut stdout, &mut stderr));
 }
 
 #[cfg(test)]
 mod tests {
     use std::process::Command;
     use std::process::Output;
-    use count_character;
-
-    #[test]
-    fn count_character_number_lines() {
-        let character_count: &mut usize = &mut 0;
-
-        count_character(character_count, &true, &false);
-        assert_eq!(character_count, &mut 1);
-    }
-
-    #[test]
-    fn count_character_number_none_empty_lines() {
-        let character_count: &mut usize = &mut 0;
-
-        count_character(character_count, &false, &true);
-        assert_eq!(character_count, &mut 1);
-    }
-
-    #[test]
-    fn count_character_number_lines_and_none_blank_lines() {
-        let character_count: &mut usize = &mut 0;
-
-        count_character(character_count, &true, &true);
-        assert_eq!(character_count, &mut 1);
-    }
-
-    #[test]
-    fn count_character_number_no_lines() {
-        let character_count: &mut usize = &mut 0;
-
-        count_character(character_count, &false, &false);
-        assert_eq!(character_count, &mut 0);
-    }
+    use std::process::Stdio;
 
     fn run_cat_command(arguments: &[&str]) -> Output {
         return Command::new("target/debug/cat")
@@ -421,6 +521,47 @@ mod tests {
 
         assert!(String::from_utf8_lossy(&output.stdout).is_empty());
         assert!(!&output.status.success());
-        assert!(String::from_utf8_lossy(&output.stderr).contains("entity not found"));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("No such file or directory"));
+    }
+
+    #[test]
+    fn continues_past_missing_file_and_reports_failure() {
+        let output = run_cat_command(&["testing/none_existent_file", "testing/file_with_text"]);
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), String::from("FILE IS NOT EMPTY\n"));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("No such file or directory"));
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    #[test]
+    fn broken_pipe_on_stdout_exits_cleanly() {
+        let mut child = Command::new("target/debug/cat")
+            .arg("testing/large_file")
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute command");
+
+        // Drop the read end without draining it so a later write hits a broken pipe.
+        drop(child.stdout.take());
+
+        let status = child.wait().expect("Failed to wait on child");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn zero_terminated_show_nonprinting_leaves_separator_alone() {
+        let output = run_cat_command(&["-v", "-z", "testing/zero_terminated_file"]);
+
+        assert!(&output.status.success());
+        assert_eq!(output.stdout, b"foo\0bar\0".to_vec());
+        assert!(!String::from_utf8_lossy(&output.stdout).contains("^@"));
+    }
+
+    #[test]
+    fn zero_terminated_show_ends_marks_record_before_nul() {
+        let output = run_cat_command(&["-E", "-z", "testing/zero_terminated_file"]);
+
+        assert!(&output.status.success());
+        assert_eq!(output.stdout, b"foo$\0bar$\0".to_vec());
     }
 }
\ No newline at end of file